@@ -2,10 +2,64 @@
 //! `plato-hook-helper` is a set of utility functions to assist with writing fetch hooks for the
 //! [Plato](https://github.com/baskerville/plato) e-reader document system.
 
-use std::io::{BufRead, BufReader, Read, Stdin, Stdout, Write};
+use std::io::{self, BufRead, BufReader, Read, Stdin, Stdout, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
+/// Set by [`handle_shutdown_signal`] when `SIGTERM` or `SIGINT` is received, and polled by
+/// [`PlatoHelper::should_stop`] and the background thread [`PlatoHelper::on_shutdown`] spawns.
+static SHOULD_STOP: AtomicBool = AtomicBool::new(false);
+
+static INSTALL_SIGNAL_HANDLER: Once = Once::new();
+
+/// How often [`PlatoHelper::read_line_blocking`] (and the background thread
+/// [`PlatoHelper::on_shutdown`] spawns) checks [`SHOULD_STOP`] between polls of the reader's file
+/// descriptor.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The cleanup closure registered via [`PlatoHelper::on_shutdown`], run once from the background
+/// watcher thread rather than from within the signal handler itself.
+static SHUTDOWN_HANDLER: Mutex<Option<Box<dyn FnOnce() + Send>>> = Mutex::new(None);
+
+/// Locks [`SHUTDOWN_HANDLER`], recovering it if a previous panic poisoned it. A panicking
+/// handler must never leave the watcher thread unable to take and run the next one.
+fn shutdown_handler_lock() -> std::sync::MutexGuard<'static, Option<Box<dyn FnOnce() + Send>>> {
+    SHUTDOWN_HANDLER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Async-signal-safe handler for `SIGTERM`/`SIGINT`: it only flips an atomic flag, leaving
+/// anything that isn't safe to do in signal context to the background thread
+/// [`PlatoHelper::on_shutdown`] spawns.
+extern "C" fn handle_shutdown_signal(_signal: libc::c_int) {
+    SHOULD_STOP.store(true, Ordering::SeqCst);
+}
+
+/// Installs [`handle_shutdown_signal`] for `SIGTERM`/`SIGINT` via `sigaction`. The handler itself
+/// only flips [`SHOULD_STOP`]; a hook parked in [`PlatoHelper::wait_for_network_blocking`] or
+/// [`PlatoHelper::events`] notices because those methods poll the reader's file descriptor in
+/// [`SHUTDOWN_POLL_INTERVAL`] slices and check the flag between polls (see
+/// [`PlatoHelper::read_line_blocking`]) — not because the signal interrupts a blocked read with
+/// `EINTR`. `BufRead::read_line` silently retries through `EINTR` internally, so a plain blocking
+/// read would never notice the signal arrived at all, no matter how `sa_flags` is set here.
+fn install_signal_handler() {
+    INSTALL_SIGNAL_HANDLER.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle_shutdown_signal as *const () as usize;
+        libc::sigemptyset(&mut action.sa_mask);
+        action.sa_flags = 0;
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    });
+}
+
 /// The status of the e-reader's Wi-Fi.
 pub enum WifiStatus {
     /// The Wi-Fi is turned on, allowing network connections to be made.
@@ -37,6 +91,61 @@ struct WifiEvent {
     enable: bool,
 }
 
+/// The on-disk file a [`DocumentInfo`] describes.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug, Clone)]
+pub struct FileInfo {
+    /// The path of the document on disk, relative to the library's root.
+    pub path: PathBuf,
+}
+
+/// The metadata Plato associates with a document in its library. Passed to
+/// [`PlatoHelper::add_document`] so a fetch hook can hand over a typed description of what it just
+/// downloaded instead of hand-rolling the JSON Plato expects.
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug, Clone)]
+pub struct DocumentInfo {
+    /// The document's file.
+    pub file: FileInfo,
+    /// The document's title, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The document's author, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// The year the document was published, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<String>,
+    /// A unique identifier for the document (e.g. an ISBN or DOI), if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+}
+
+/// The structure of an `addDocument` event. Used to tell Plato a new document has been downloaded
+/// and should be imported into the library.
+#[derive(Serialize, Deserialize)]
+struct AddDocumentEvent {
+    r#type: String,
+    info: DocumentInfo,
+}
+
+/// The structure of a `removeDocument` event. Used to tell Plato a document should be removed from
+/// the library.
+#[derive(Serialize, Deserialize)]
+struct RemoveDocumentEvent {
+    r#type: String,
+    path: PathBuf,
+}
+
+/// The structure of an outbound `search` event. Used to ask Plato to search the library.
+#[derive(Serialize, Deserialize)]
+struct SearchRequestEvent {
+    r#type: String,
+    query: String,
+    #[serde(rename = "sortBy", skip_serializing_if = "Option::is_none")]
+    sort_by: Option<String>,
+    #[serde(rename = "reverseOrder")]
+    reverse_order: bool,
+}
+
 /// The structure of a network event. Used to signal when the device's network status changes.
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct NetworkEvent {
@@ -44,52 +153,141 @@ pub struct NetworkEvent {
     status: String,
 }
 
+/// The structure of a search event. Sent by Plato when a hook-driven search has been requested.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct SearchEvent {
+    r#type: String,
+    query: String,
+}
+
+/// The structure of a `getNetworkReport` event. Used to ask Plato for the current state of the
+/// Wi-Fi radio, optionally triggering a fresh scan first.
+#[derive(Serialize, Deserialize)]
+struct NetworkReportRequestEvent {
+    r#type: String,
+    scan: bool,
+}
+
+/// A single Wi-Fi network visible to the device, as found by the most recent scan.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VisibleSsid {
+    /// The network's SSID.
+    pub ssid: String,
+    /// The received signal strength, in dBm. Higher (less negative) means a stronger signal; a
+    /// hook can check this before deciding whether the connection is strong enough to start a
+    /// large download.
+    pub rssi: i32,
+}
+
+/// Plato's reply to a [`PlatoHelper::request_network_report`] call, describing the current state
+/// of the Wi-Fi radio.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NetworkReport {
+    r#type: String,
+    /// The IP addresses assigned to the device, if it's currently associated with a network.
+    pub ip_addresses: Vec<String>,
+    /// The SSID the device is currently associated with, or `None` if it's disconnected.
+    pub ssid: Option<String>,
+    /// The Wi-Fi networks visible to the device, as of the last scan.
+    pub visible_ssids: Vec<VisibleSsid>,
+}
+
+/// Every event Plato may send to a hook on its standard input.
+///
+/// Unlike [`PlatoHelper::read_json_blocking`], which silently discards any line that doesn't
+/// deserialize into the type the caller asked for, [`PlatoHelper::events`] tags every line by its
+/// `"type"` field and yields it, so a hook waiting on one kind of event doesn't throw away the
+/// others Plato interleaves on the same stream.
+#[derive(Debug)]
+pub enum Event {
+    /// The device's network status has changed.
+    Network(NetworkEvent),
+    /// A search has been requested of the hook.
+    Search(SearchEvent),
+    /// Plato's reply to a [`PlatoHelper::request_network_report`] call.
+    NetworkReport(NetworkReport),
+    /// An event Plato sent whose `"type"` this crate doesn't yet model. The raw JSON is kept so
+    /// the hook author can still inspect it.
+    Unknown { r#type: String, raw: serde_json::Value },
+}
+
+impl Event {
+    /// Whether this event is unsolicited, i.e. Plato sent it spontaneously rather than as the
+    /// direct reply to a request the hook made. A hook blocking for a specific reply can use this
+    /// to skip past spontaneous status reports instead of mistaking one for the reply it's
+    /// waiting on.
+    pub fn is_unsolicited(&self) -> bool {
+        match self {
+            Event::Network(_) => true,
+            // A search is initiated by the user inside Plato, not requested by the hook, so it's
+            // just as unsolicited as a network status change.
+            Event::Search(_) => true,
+            Event::NetworkReport(_) => false,
+            Event::Unknown { .. } => true,
+        }
+    }
+
+    fn parse(line: &str) -> io::Result<Event> {
+        let value: serde_json::Value =
+            serde_json::from_str(line).map_err(io::Error::other)?;
+        let r#type = value
+            .get("type")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "event missing \"type\""))?
+            .to_string();
+
+        match r#type.as_str() {
+            "network" => serde_json::from_value(value)
+                .map(Event::Network)
+                .map_err(io::Error::other),
+            "search" => serde_json::from_value(value)
+                .map(Event::Search)
+                .map_err(io::Error::other),
+            "networkReport" => serde_json::from_value(value)
+                .map(Event::NetworkReport)
+                .map_err(io::Error::other),
+            _ => Ok(Event::Unknown { r#type, raw: value }),
+        }
+    }
+}
+
 /// A helper struct for interacting with the Plato e-reader software. Holds a writer to output JSON
 /// to, by default `stdout`.
 pub struct PlatoHelper<W: Write, R: Read> {
     writer: W,
-    reader: R,
+    reader: BufReader<R>,
+    /// Bytes of a line read by [`PlatoHelper::read_line_timeout`] that hadn't seen a `\n` yet
+    /// when its deadline ran out, kept so the next call resumes mid-line instead of the stream
+    /// desyncing.
+    pending_line: Vec<u8>,
 }
 
 impl Default for PlatoHelper<Stdout, Stdin> {
     fn default() -> Self {
-        PlatoHelper {
-            writer: std::io::stdout(),
-            reader: std::io::stdin(),
-        }
+        Self::new(std::io::stdout(), std::io::stdin())
     }
 }
 
 impl<W: Write, R: Read> PlatoHelper<W, R> {
     pub fn new(writer: W, reader: R) -> Self {
-        PlatoHelper { writer, reader }
+        install_signal_handler();
+        PlatoHelper {
+            writer,
+            reader: BufReader::new(reader),
+            pending_line: Vec::new(),
+        }
     }
 
-    /// Take's a serializable struct and writes it to the internal writer as a JSON string.
+    /// Take's a serializable struct and writes it to the internal writer as a newline-terminated
+    /// JSON string. Plato reads hook stdout as newline-delimited JSON, and so does this crate's
+    /// own reader, so every event needs its own line.
     fn write_json<T: Serialize>(&mut self, value: &T) -> std::io::Result<()> {
-        let json = serde_json::to_string(value)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let json = serde_json::to_string(value).map_err(std::io::Error::other)?;
         self.writer.write_all(json.as_bytes())?;
+        self.writer.write_all(b"\n")?;
         Ok(())
     }
 
-    /// Reads JSON from the internal reader, blocking until a valid JSON string is received matching
-    /// the given type `J`.
-    fn read_json_blocking<J>(&mut self) -> Result<J, std::io::Error>
-    where
-        J: for<'de> Deserialize<'de>,
-    {
-        let mut reader = BufReader::new(&mut self.reader);
-        let mut input = String::new();
-        loop {
-            input.clear();
-            reader.read_line(&mut input)?;
-            if let Ok(json) = serde_json::from_str(&input) {
-                return Ok(json);
-            }
-        }
-    }
-
     /// Displays a notification on the device with the given `message`.
     pub fn display_notification(&mut self, message: &str) -> std::io::Result<()> {
         let event = NotificationEvent {
@@ -108,11 +306,278 @@ impl<W: Write, R: Read> PlatoHelper<W, R> {
         self.write_json(&event)
     }
 
-    /// Waits until a network event is received from the internal reader.
-    /// This function will block indefinitely until a valid event is received or an IO error occurs.
-    pub fn wait_for_network_blocking(&mut self) -> Result<NetworkEvent, std::io::Error> {
+    /// Tells Plato that a document has been downloaded and should be added to the library.
+    pub fn add_document(&mut self, info: DocumentInfo) -> std::io::Result<()> {
+        let event = AddDocumentEvent {
+            r#type: "addDocument".to_string(),
+            info,
+        };
+        self.write_json(&event)
+    }
+
+    /// Tells Plato to remove the document at `path` from the library.
+    pub fn remove_document(&mut self, path: &Path) -> std::io::Result<()> {
+        let event = RemoveDocumentEvent {
+            r#type: "removeDocument".to_string(),
+            path: path.to_path_buf(),
+        };
+        self.write_json(&event)
+    }
+
+    /// Asks Plato to search the library for `query`, optionally sorting results by `sort_by`
+    /// (e.g. `"title"` or `"author"`) and reversing that order.
+    pub fn search(
+        &mut self,
+        query: &str,
+        sort_by: Option<&str>,
+        reverse_order: bool,
+    ) -> std::io::Result<()> {
+        let event = SearchRequestEvent {
+            r#type: "search".to_string(),
+            query: query.to_string(),
+            sort_by: sort_by.map(|s| s.to_string()),
+            reverse_order,
+        };
+        self.write_json(&event)
+    }
+
+    /// Asks Plato for a [`NetworkReport`] describing the current state of the Wi-Fi radio. If
+    /// `scan` is `true`, Plato first performs a fresh Wi-Fi scan before replying; otherwise it
+    /// replies with what it already knows.
+    pub fn request_network_report(&mut self, scan: bool) -> std::io::Result<()> {
+        let event = NetworkReportRequestEvent {
+            r#type: "getNetworkReport".to_string(),
+            scan,
+        };
+        self.write_json(&event)
+    }
+
+    /// Registers `handler` to run once Plato terminates the hook (by sending `SIGTERM` or
+    /// `SIGINT`, e.g. because the reader closed or the device went to sleep). A background thread
+    /// watches for the signal, runs `handler` outside of signal context so it can do normal
+    /// things like delete a partial download and emit a final notification, then exits the
+    /// process once `handler` returns (or panics).
+    ///
+    /// This is the whole shutdown path: once registered, the watcher thread owns exiting the
+    /// process on signal, so a hook should use either `on_shutdown` *or* its own
+    /// [`PlatoHelper::should_stop`]-driven exit, not both, or the two will race.
+    pub fn on_shutdown<F>(&mut self, handler: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        install_signal_handler();
+        *shutdown_handler_lock() = Some(Box::new(handler));
+
+        thread::spawn(|| {
+            while !SHOULD_STOP.load(Ordering::SeqCst) {
+                thread::sleep(SHUTDOWN_POLL_INTERVAL);
+            }
+            let handler = shutdown_handler_lock().take();
+            if let Some(handler) = handler {
+                // Run outside the lock so a panicking handler can't leave it poisoned for a
+                // concurrent on_shutdown caller, and so the exit below still happens either way -
+                // a signal must never leave the process unkillable.
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(handler));
+            }
+            std::process::exit(0);
+        });
+    }
+
+    /// Whether Plato has asked the hook to shut down. A long-running fetch loop should poll this
+    /// between documents and stop early once it's `true`.
+    pub fn should_stop(&self) -> bool {
+        SHOULD_STOP.load(Ordering::SeqCst)
+    }
+}
+
+impl<W: Write, R: Read + AsRawFd> PlatoHelper<W, R> {
+    /// Blocks until the internal reader becomes readable, reaches EOF, or `timeout` elapses, by
+    /// polling its file descriptor. Returns `Ok(true)` if a subsequent read won't block, `Ok(false)`
+    /// if `timeout` expired first.
+    ///
+    /// A pipe whose write end has already closed reports `POLLHUP` (and a socket in error state
+    /// reports `POLLERR`) without ever setting `POLLIN`, even though `read`/`fill_buf` on it
+    /// returns immediately with EOF. Checking only `POLLIN` would make this wait out the full
+    /// `timeout` on every call once the peer is gone, instead of reporting readiness right away.
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.reader.get_ref().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(pollfd.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0),
+        }
+    }
+
+    /// Reads a single line, honouring `deadline` only for bytes not already sitting in the
+    /// internal buffer. A previous call may have buffered a second line along with the one it
+    /// returned (Plato can write several events in one go), so already-buffered bytes are drained
+    /// first without touching the fd or the deadline at all. Once the buffer runs dry, each
+    /// further byte is only waited for up to `deadline`, so a line Plato sends a few bytes at a
+    /// time can't block past it.
+    ///
+    /// Bytes read before a line's `\n` arrives are accumulated in `self.pending_line` rather than
+    /// a local buffer: if `deadline` expires mid-line, those bytes stay there so the next call
+    /// resumes where this one left off instead of the stream desyncing. The whole line is decoded
+    /// as UTF-8 once it's complete, not chunk by chunk, so a multibyte character split across two
+    /// reads doesn't get corrupted at the boundary.
+    fn read_line_timeout(&mut self, deadline: Instant) -> io::Result<LineRead> {
+        loop {
+            while !self.reader.buffer().is_empty() {
+                let buf = self.reader.buffer();
+                let newline_pos = buf.iter().position(|&b| b == b'\n');
+                let chunk_end = newline_pos.map_or(buf.len(), |pos| pos + 1);
+                self.pending_line.extend_from_slice(&buf[..chunk_end]);
+                self.reader.consume(chunk_end);
+                if newline_pos.is_some() {
+                    let line = String::from_utf8_lossy(&self.pending_line).into_owned();
+                    self.pending_line.clear();
+                    return Ok(LineRead::Line(line));
+                }
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_readable(remaining)? {
+                return Ok(LineRead::TimedOut);
+            }
+
+            // The fd is readable, so this fills the buffer with at least one more byte without
+            // blocking indefinitely; an empty result means the reader hit EOF.
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(LineRead::Eof);
+            }
+        }
+    }
+
+    /// Reads a single line, blocking until one arrives, but never waiting longer than
+    /// [`SHUTDOWN_POLL_INTERVAL`] without checking [`SHOULD_STOP`]. This is what actually lets a
+    /// hook parked in [`PlatoHelper::wait_for_network_blocking`] or [`PlatoHelper::events`] notice
+    /// a shutdown signal promptly: `install_signal_handler`'s handler only flips an atomic flag,
+    /// and `BufRead::read_line` would otherwise retry straight through the `EINTR` that flag-flip
+    /// delivers. Returns `Ok(None)` once the reader reaches EOF.
+    ///
+    /// [`PlatoHelper::should_stop`] is only checked after a poll slice times out empty, not before
+    /// it, so a line Plato already finished writing (and that's sitting complete in the internal
+    /// buffer) is always delivered even if the shutdown signal arrives in the same instant.
+    fn read_line_blocking(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_line_timeout(Instant::now() + SHUTDOWN_POLL_INTERVAL)? {
+                LineRead::Line(line) => return Ok(Some(line)),
+                LineRead::Eof => return Ok(None),
+                LineRead::TimedOut => {
+                    if SHOULD_STOP.load(Ordering::SeqCst) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Interrupted,
+                            "shutdown requested",
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads JSON from the internal reader, blocking until a valid JSON string matching `J` is
+    /// received. Polls in [`SHUTDOWN_POLL_INTERVAL`] slices via
+    /// [`PlatoHelper::read_line_blocking`] so a shutdown signal is noticed promptly instead of
+    /// being silently retried the way a plain `BufRead::read_line` would retry through `EINTR`.
+    fn read_json_blocking<J>(&mut self) -> io::Result<J>
+    where
+        J: for<'de> Deserialize<'de>,
+    {
+        loop {
+            match self.read_line_blocking()? {
+                Some(input) => {
+                    if let Ok(json) = serde_json::from_str(&input) {
+                        return Ok(json);
+                    }
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "reader closed before a matching event arrived",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Parses every line Plato sends on the internal reader into a tagged [`Event`], yielding each
+    /// one rather than discarding events the caller wasn't expecting. Iteration ends once the
+    /// reader reaches EOF, or once [`PlatoHelper::should_stop`] flips, in which case the final item
+    /// is an `Err(ErrorKind::Interrupted)` (see [`PlatoHelper::read_line_blocking`]) rather than the
+    /// iterator spinning on that same error forever.
+    pub fn events(&mut self) -> impl Iterator<Item = io::Result<Event>> + '_ {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            match self.read_line_blocking() {
+                Ok(Some(line)) => Some(Event::parse(&line)),
+                Ok(None) => {
+                    done = true;
+                    None
+                }
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Reads JSON from the internal reader, returning `Ok(None)` if no valid line matching `J`
+    /// arrives before `timeout` elapses. Unlike [`PlatoHelper::read_json_blocking`], a hook calling
+    /// this can be cancelled rather than wedging forever waiting on Plato.
+    fn read_json_timeout<J>(&mut self, timeout: Duration) -> io::Result<Option<J>>
+    where
+        J: for<'de> Deserialize<'de>,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.read_line_timeout(deadline)? {
+                LineRead::TimedOut | LineRead::Eof => return Ok(None),
+                LineRead::Line(input) => {
+                    if let Ok(json) = serde_json::from_str(&input) {
+                        return Ok(Some(json));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits until a network event is received from the internal reader, checking
+    /// [`PlatoHelper::should_stop`] every [`SHUTDOWN_POLL_INTERVAL`] so a shutdown signal is
+    /// noticed promptly rather than blocking past it (see [`PlatoHelper::read_line_blocking`]).
+    pub fn wait_for_network_blocking(&mut self) -> io::Result<NetworkEvent> {
         self.read_json_blocking()
     }
+
+    /// Waits until a network event is received from the internal reader, or `timeout` elapses
+    /// without one arriving, in which case `Ok(None)` is returned. Use this instead of
+    /// [`PlatoHelper::wait_for_network_blocking`] when the hook can't afford to wedge indefinitely,
+    /// e.g. while racing a Wi-Fi connection window.
+    pub fn wait_for_network_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> io::Result<Option<NetworkEvent>> {
+        self.read_json_timeout(timeout)
+    }
+}
+
+/// Outcome of [`PlatoHelper::read_line_timeout`]: a complete line, the deadline expiring first, or
+/// the reader reaching EOF. Kept as three distinct outcomes (rather than collapsing "nothing yet"
+/// and "nothing ever again" into one `None`) so [`PlatoHelper::read_line_blocking`] can tell a
+/// hook's read loop to keep polling from a stream that's actually closed for good.
+enum LineRead {
+    Line(String),
+    TimedOut,
+    Eof,
 }
 
 #[cfg(test)]
@@ -135,18 +600,288 @@ mod tests {
             message: "Hello, World!".to_string(),
         };
 
-        let event = serde_json::to_string(&notification).unwrap();
+        let event = serde_json::to_string(&notification).unwrap() + "\n";
         let output = String::from_utf8(buffer).unwrap();
 
         assert_eq!(event, output);
     }
 
+    #[test]
+    fn add_document_formatting() {
+        let mut buffer = Vec::new();
+        let writer = BufWriter::new(&mut buffer);
+        let info = DocumentInfo {
+            file: FileInfo {
+                path: PathBuf::from("book.epub"),
+            },
+            title: Some("A Book".to_string()),
+            author: Some("Someone".to_string()),
+            year: None,
+            identifier: None,
+        };
+        {
+            let mut plato = PlatoHelper::new(writer, std::io::stdin());
+            plato.add_document(info.clone()).unwrap();
+        }
+
+        let event = AddDocumentEvent {
+            r#type: "addDocument".to_string(),
+            info,
+        };
+
+        let expected = serde_json::to_string(&event).unwrap() + "\n";
+        let output = String::from_utf8(buffer).unwrap();
+
+        assert_eq!(expected, output);
+        // Absent metadata is omitted entirely, not emitted as explicit JSON `null`s.
+        assert!(!output.contains("null"));
+    }
+
+    #[test]
+    fn search_formatting() {
+        let mut buffer = Vec::new();
+        let writer = BufWriter::new(&mut buffer);
+        {
+            let mut plato = PlatoHelper::new(writer, std::io::stdin());
+            plato.search("rust", Some("title"), true).unwrap();
+        }
+
+        let output = String::from_utf8(buffer).unwrap();
+
+        assert!(output.contains("\"sortBy\":\"title\""));
+        assert!(output.contains("\"reverseOrder\":true"));
+        assert!(!output.contains("null"));
+    }
+
+    #[test]
+    fn events_tags_and_yields_every_line() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        let json = "{\"type\": \"network\", \"status\": \"up\"}\n{\"type\": \"search\", \"query\": \"rust\"}\n{\"type\": \"sleep\"}\n";
+        writer.write_all(json.as_bytes()).unwrap();
+        drop(writer); // close the write end so `events()` sees EOF after the last line
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let events: Vec<Event> = plato.events().map(Result::unwrap).collect();
+
+        assert!(matches!(&events[0], Event::Network(n) if n.status == "up"));
+        assert!(matches!(&events[1], Event::Search(s) if s.query == "rust"));
+        assert!(matches!(&events[2], Event::Unknown { r#type, .. } if r#type == "sleep"));
+        assert!(events[0].is_unsolicited());
+        assert!(events[1].is_unsolicited());
+    }
+
+    #[test]
+    fn events_parses_network_report() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        let json = r#"{"type": "networkReport", "ip_addresses": ["192.168.1.5"], "ssid": "HomeWifi", "visible_ssids": [{"ssid": "HomeWifi", "rssi": -45}]}"#;
+        writeln!(writer, "{json}").unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let event = plato.events().next().unwrap().unwrap();
+
+        assert!(!event.is_unsolicited());
+        match event {
+            Event::NetworkReport(report) => {
+                assert_eq!(report.ssid.as_deref(), Some("HomeWifi"));
+                assert_eq!(report.ip_addresses, vec!["192.168.1.5".to_string()]);
+                assert_eq!(report.visible_ssids[0].rssi, -45);
+            }
+            other => panic!("expected NetworkReport, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn wait_for_network_timeout_returns_some_when_data_arrives() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        writeln!(writer, "{{\"type\": \"network\", \"status\": \"up\"}}").unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let result = plato
+            .wait_for_network_timeout(Duration::from_millis(500))
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Some(NetworkEvent {
+                r#type: "network".to_string(),
+                status: "up".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn wait_for_network_timeout_drains_a_second_buffered_event() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        // Plato delivering two events in a single write is what used to make the second call
+        // spuriously time out: it only polled the fd, never looked at what was already buffered.
+        write!(
+            writer,
+            "{{\"type\": \"network\", \"status\": \"up\"}}\n{{\"type\": \"network\", \"status\": \"down\"}}\n"
+        )
+        .unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let first = plato
+            .wait_for_network_timeout(Duration::from_millis(500))
+            .unwrap();
+        let second = plato
+            .wait_for_network_timeout(Duration::from_millis(10))
+            .unwrap();
+
+        assert_eq!(first.map(|e| e.status), Some("up".to_string()));
+        assert_eq!(second.map(|e| e.status), Some("down".to_string()));
+    }
+
+    #[test]
+    fn wait_for_network_timeout_does_not_block_on_a_partial_line() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+        use std::time::Instant;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        // No trailing newline: a plain `read_line` after `poll` would block here forever.
+        write!(writer, "{{\"type\": \"network\"").unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let started = Instant::now();
+        let result = plato
+            .wait_for_network_timeout(Duration::from_millis(100))
+            .unwrap();
+
+        assert_eq!(result, None);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn wait_for_network_timeout_resumes_a_partial_line_after_a_timeout() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        write!(writer, "{{\"type\": \"netw").unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let timed_out = plato
+            .wait_for_network_timeout(Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(timed_out, None);
+
+        // The bytes buffered before the timeout must still be there, or this completes to
+        // garbage instead of a valid event.
+        writeln!(writer, "ork\", \"status\": \"up\"}}").unwrap();
+        let result = plato
+            .wait_for_network_timeout(Duration::from_millis(500))
+            .unwrap();
+
+        assert_eq!(result.map(|e| e.status), Some("up".to_string()));
+    }
+
+    #[test]
+    fn wait_for_network_timeout_does_not_corrupt_a_multibyte_char_split_across_reads() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        // "café" ends in a 2-byte UTF-8 sequence (0xC3 0xA9); split the write right in the
+        // middle of it so a naive per-chunk `from_utf8_lossy` would replace it with U+FFFD.
+        let mut first_chunk = b"{\"type\": \"network\", \"status\": \"caf".to_vec();
+        first_chunk.push(0xC3);
+        writer.write_all(&first_chunk).unwrap();
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let timed_out = plato
+            .wait_for_network_timeout(Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(timed_out, None);
+
+        let mut second_chunk = vec![0xA9];
+        second_chunk.extend_from_slice(b"\"}\n");
+        writer.write_all(&second_chunk).unwrap();
+        let result = plato
+            .wait_for_network_timeout(Duration::from_millis(500))
+            .unwrap();
+
+        assert_eq!(result.map(|e| e.status), Some("café".to_string()));
+    }
+
+    #[test]
+    fn wait_for_network_timeout_returns_none_when_nothing_arrives() {
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let _writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        let mut plato = PlatoHelper::new(Vec::new(), reader);
+        let result = plato
+            .wait_for_network_timeout(Duration::from_millis(50))
+            .unwrap();
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn should_stop_is_false_before_any_shutdown_signal() {
+        let plato = PlatoHelper::new(Vec::new(), Cursor::new(""));
+        assert!(!plato.should_stop());
+    }
+
     #[test]
     fn wait_for_network_blocking_deserializes_correctly() {
-        let json = r#"{"type": "network", "status": "up"}"#;
-        let reader = Cursor::new(json);
+        use std::fs::File;
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+
+        writeln!(writer, "{{\"type\": \"network\", \"status\": \"up\"}}").unwrap();
+
         let mut plato = PlatoHelper::new(Vec::new(), reader);
-        let result: Result<NetworkEvent, std::io::Error> = plato.wait_for_network_blocking();
+        let result: io::Result<NetworkEvent> = plato.wait_for_network_blocking();
 
         assert_eq!(
             result.unwrap(),